@@ -0,0 +1,42 @@
+use crate::params::{FanParams, Params};
+use atm_refraction::Path;
+use std::{
+    fs::{create_dir_all, File},
+    io::Write,
+};
+
+/// Launch a fan of `fan.count` rays, evenly spaced between `fan.min_angle` and
+/// `fan.max_angle`, and write each ray's sampled trajectory to its own CSV
+/// file in `fan.output_dir`.
+pub fn run_fan(params: &Params, fan: &FanParams) {
+    create_dir_all(&fan.output_dir)
+        .unwrap_or_else(|_| panic!("couldn't create fan output directory {:?}", fan.output_dir));
+
+    let step_angle = if fan.count > 1 {
+        (fan.max_angle - fan.min_angle) / (fan.count - 1) as f64
+    } else {
+        0.0
+    };
+
+    for i in 0..fan.count {
+        let angle = fan.min_angle + step_angle * i as f64;
+        let ray = params
+            .env
+            .cast_ray(params.ray.start_h, angle.to_radians(), params.straight);
+
+        let path = format!("{}/ray_{:03}.csv", fan.output_dir, i);
+        let mut file = File::create(&path)
+            .unwrap_or_else(|_| panic!("couldn't create fan output file {:?}", path));
+        writeln!(file, "distance_km,altitude_m,angle_deg")
+            .unwrap_or_else(|_| panic!("failed writing to fan output file {:?}", path));
+
+        let mut dist = 0.0;
+        while dist <= fan.dmax {
+            let h = ray.h_at_dist(dist);
+            let ang = ray.angle_at_dist(dist).to_degrees();
+            writeln!(file, "{},{},{}", dist / 1e3, h, ang)
+                .unwrap_or_else(|_| panic!("failed writing to fan output file {:?}", path));
+            dist += fan.ds;
+        }
+    }
+}
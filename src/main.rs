@@ -1,23 +1,12 @@
+mod fan;
 mod params;
+mod query;
+mod scenario;
+mod visibility;
 
 use crate::params::*;
-use atm_refraction::{EarthShape, Path};
-
-fn find_dist_for_h(ray: &dyn Path, tgt_h: f64) -> f64 {
-    let (mut min_dist, mut max_dist) = (0.0, 5000e3);
-
-    while max_dist - min_dist > 0.00001 {
-        let cur_dist = 0.5 * (min_dist + max_dist);
-        let h = ray.h_at_dist(cur_dist);
-        if h > tgt_h {
-            max_dist = cur_dist;
-        } else {
-            min_dist = cur_dist;
-        }
-    }
-
-    0.5 * (min_dist + max_dist)
-}
+use crate::query::{dispersion, eval_output};
+use crate::visibility::check_visibility;
 
 fn main() {
     let params = parse_arguments();
@@ -31,6 +20,16 @@ fn main() {
         println!("Starting altitude: {} m ASL", params.ray.start_h);
     }
 
+    if let Some(ref fan_params) = params.fan {
+        fan::run_fan(&params, fan_params);
+        return;
+    }
+
+    if let Some(ref scenario) = params.scenario {
+        scenario::run_scenario(&params, scenario);
+        return;
+    }
+
     let ray = create_path(&params);
 
     if params.straight && params.verbose {
@@ -41,62 +40,114 @@ fn main() {
     }
 
     for output in &params.output {
+        if let Output::Visibility {
+            ref terrain,
+            ds,
+            dmax,
+            tgt_dist,
+            tgt_h,
+        } = *output
+        {
+            let visibility = check_visibility(&*ray, terrain, ds, dmax, tgt_dist, tgt_h);
+            match visibility.occlusion_dist {
+                Some(dist) => {
+                    if params.verbose {
+                        println!(
+                            "Occlusion distance: {} kilometers, target {}",
+                            dist / 1000.0,
+                            if visibility.target_visible {
+                                "visible"
+                            } else {
+                                "blocked"
+                            }
+                        );
+                    } else {
+                        println!("{} {}", dist / 1000.0, visibility.target_visible);
+                    }
+                }
+                None => {
+                    if params.verbose {
+                        println!(
+                            "No occlusion found up to the search limit, target {}",
+                            if visibility.target_visible {
+                                "visible"
+                            } else {
+                                "blocked"
+                            }
+                        );
+                    } else {
+                        println!("none {}", visibility.target_visible);
+                    }
+                }
+            }
+            continue;
+        }
+
+        if let Output::Dispersion {
+            wavelength1,
+            wavelength2,
+        } = *output
+        {
+            let value = dispersion(
+                &params.env,
+                params.ray.start_h,
+                &params.ray.dir,
+                params.straight,
+                wavelength1,
+                wavelength2,
+            );
+            if params.verbose {
+                println!(
+                    "Dispersion between {} nm and {} nm: {} degrees",
+                    wavelength1 * 1e9,
+                    wavelength2 * 1e9,
+                    value
+                );
+            } else {
+                println!("{}", value);
+            }
+            continue;
+        }
+
+        let value = eval_output(&*ray, &params.env, params.ray.start_h, output);
         match *output {
             Output::HAtDist(dist) => {
                 if params.verbose {
-                    println!("Altitude at distance {} km: {}", dist, ray.h_at_dist(dist));
+                    println!("Altitude at distance {} km: {}", dist / 1000.0, value);
                 } else {
-                    println!("{}", ray.h_at_dist(dist));
+                    println!("{}", value);
                 }
             }
             Output::Angle => {
                 if params.verbose {
-                    println!(
-                        "Starting angle: {} degrees",
-                        ray.angle_at_dist(0.0).to_degrees()
-                    );
+                    println!("Starting angle: {} degrees", value);
                 } else {
-                    println!("{}", ray.angle_at_dist(0.0).to_degrees());
+                    println!("{}", value);
                 }
             }
             Output::Astronomical => {
-                let start_ang = ray.angle_at_dist(0.0);
-                let dist_to_200km = find_dist_for_h(&*ray, 2e5); // 2e5 m == 200 km
-                let final_ang = ray.angle_at_dist(dist_to_200km);
-                let deflection_ang = if let EarthShape::Spherical { radius } = params.env.shape {
-                    start_ang - final_ang + dist_to_200km / radius
-                } else {
-                    start_ang - final_ang
-                };
                 if params.verbose {
-                    println!(
-                        "Astronomical refraction angle: {} degrees",
-                        deflection_ang.to_degrees()
-                    );
+                    println!("Astronomical refraction angle: {} degrees", value);
                 } else {
-                    println!("{}", deflection_ang.to_degrees());
+                    println!("{}", value);
                 }
             }
             Output::HorizonAngle => {
-                let dist_to_target_h = find_dist_for_h(&*ray, params.ray.start_h);
-                let ang = ray.angle_at_dist(dist_to_target_h);
                 if params.verbose {
-                    println!("Angle to the horizon: {} degrees", -ang.to_degrees());
+                    println!("Angle to the horizon: {} degrees", value);
                 } else {
-                    println!("{}", -ang.to_degrees());
+                    println!("{}", value);
                 }
             }
             Output::HorizonDistance => {
-                let dist_to_target_h = find_dist_for_h(&*ray, params.ray.start_h);
                 if params.verbose {
-                    println!(
-                        "Distance to the horizon: {} kilometers",
-                        dist_to_target_h / 1000.0
-                    );
+                    println!("Distance to the horizon: {} kilometers", value);
                 } else {
-                    println!("{}", dist_to_target_h / 1000.0);
+                    println!("{}", value);
                 }
             }
+            Output::Visibility { .. } => unreachable!("handled above"),
+            Output::Dispersion { .. } => unreachable!("handled above"),
         }
     }
 }
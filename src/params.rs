@@ -1,11 +1,14 @@
+use crate::visibility::TerrainProfile;
 use atm_refraction::{
     air::{us76_atmosphere, Atmosphere, AtmosphereDef},
     EarthShape, Environment, Path,
 };
 use clap::{App, AppSettings, Arg};
+use serde_derive::Deserialize;
 use std::{fs::File, io::Read};
 
 /// Ray direction description
+#[derive(Clone, Copy, Deserialize)]
 pub enum RayDir {
     /// angle from the horizon
     Angle(f64),
@@ -23,7 +26,24 @@ pub struct RayData {
     pub dir: RayDir,
 }
 
+/// parameters for a fan launch - many rays integrated over a range of starting angles
+pub struct FanParams {
+    /// starting angle of the first ray (degrees)
+    pub min_angle: f64,
+    /// starting angle of the last ray (degrees)
+    pub max_angle: f64,
+    /// number of rays to launch
+    pub count: usize,
+    /// sampling step along each ray (meters)
+    pub ds: f64,
+    /// maximum distance to sample along each ray (meters)
+    pub dmax: f64,
+    /// directory to write one CSV file per ray into
+    pub output_dir: String,
+}
+
 /// what info to output
+#[derive(Deserialize)]
 pub enum Output {
     /// altitude at a given distance
     HAtDist(f64),
@@ -35,6 +55,57 @@ pub enum Output {
     HorizonDistance,
     /// Output the angle of deflection for rays from celestial objects
     Astronomical,
+    /// Report terrain occlusion of a target beyond the refracted horizon
+    Visibility {
+        /// terrain profile to check the ray against
+        terrain: TerrainProfile,
+        /// marching step for the occlusion search
+        ds: f64,
+        /// maximum distance to search for occlusion
+        dmax: f64,
+        /// target distance
+        tgt_dist: f64,
+        /// target altitude
+        tgt_h: f64,
+    },
+    /// Report the difference in astronomical refraction between two
+    /// wavelengths - the chromatic spread of a low-altitude object
+    Dispersion {
+        /// first wavelength (meters)
+        wavelength1: f64,
+        /// second wavelength (meters)
+        wavelength2: f64,
+    },
+}
+
+/// a single named ray query within a `Scenario`
+#[derive(Deserialize)]
+pub struct Case {
+    /// name identifying this case in the results
+    pub name: String,
+    /// starting altitude
+    pub start_h: f64,
+    /// direction of propagation
+    pub dir: RayDir,
+    /// outputs requested for this case
+    pub output: Vec<Output>,
+}
+
+/// a batch of ray queries sharing one `Environment`, loaded from a YAML file
+#[derive(Deserialize)]
+pub struct Scenario {
+    /// environment shared by every case
+    pub env: Environment,
+    /// the individual queries to run against `env`
+    pub cases: Vec<Case>,
+}
+
+/// output format for `--scenario` results
+#[derive(Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
 }
 
 pub struct Params {
@@ -43,6 +114,9 @@ pub struct Params {
     pub straight: bool,
     pub output: Vec<Output>,
     pub verbose: bool,
+    pub fan: Option<FanParams>,
+    pub scenario: Option<Scenario>,
+    pub format: Format,
 }
 
 pub fn parse_arguments() -> Params {
@@ -123,6 +197,97 @@ pub fn parse_arguments() -> Params {
                 .long("output-astronomical")
                 .help("Output the angle of deflection of rays from celestial objects")
                 .takes_value(false),
+        ).arg(
+            Arg::with_name("wavelength")
+                .long("wavelength")
+                .value_name("WAVELENGTH")
+                .help("Wavelength of light to use for refraction calculations (nanometers, default: 530)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("output_dispersion")
+                .long("output-dispersion")
+                .value_names(&["WAVELENGTH1", "WAVELENGTH2"])
+                .help("Output the angular dispersion (degrees) between two wavelengths (nanometers)")
+                .number_of_values(2),
+        ).arg(
+            Arg::with_name("visibility_terrain")
+                .long("visibility-terrain")
+                .value_name("FILE")
+                .help("Terrain profile file (distance_km,elevation_m CSV) to check the ray against")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("visibility_ds")
+                .long("visibility-ds")
+                .value_name("DS")
+                .help("Marching step for the terrain occlusion search (kilometers)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("visibility_dmax")
+                .long("visibility-dmax")
+                .value_name("DMAX")
+                .help("Maximum distance to search for terrain occlusion (kilometers)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("visibility_tgt_dist")
+                .long("visibility-tgt-dist")
+                .value_name("DISTANCE")
+                .help("Distance of the target to check for visibility (kilometers)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("visibility_tgt_h")
+                .long("visibility-tgt-h")
+                .value_name("ALTITUDE")
+                .help("Altitude of the target to check for visibility (meters)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("scenario")
+                .long("scenario")
+                .value_name("FILE")
+                .help("Run a batch of ray queries described in a YAML scenario file")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format for --scenario results (default: text)")
+                .possible_values(&["text", "json", "csv"])
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_min_angle")
+                .long("fan-min-angle")
+                .value_name("ANGLE")
+                .help("Fan launch: starting angle of the first ray (degrees)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_max_angle")
+                .long("fan-max-angle")
+                .value_name("ANGLE")
+                .help("Fan launch: starting angle of the last ray (degrees)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_count")
+                .long("fan-count")
+                .value_name("N")
+                .help("Fan launch: number of rays to launch")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_step")
+                .long("fan-step")
+                .value_name("DS")
+                .help("Fan launch: sampling step along each ray (kilometers)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_max_dist")
+                .long("fan-max-dist")
+                .value_name("DMAX")
+                .help("Fan launch: maximum distance to sample along each ray (kilometers)")
+                .takes_value(true),
+        ).arg(
+            Arg::with_name("fan_output")
+                .long("fan-output")
+                .value_name("DIR")
+                .help("Fan launch: directory to write one CSV file per ray into")
+                .takes_value(true),
         ).arg(
             Arg::with_name("straight")
                 .short("s")
@@ -146,28 +311,94 @@ pub fn parse_arguments() -> Params {
     let tgt_h = matches.value_of("target_h");
     let tgt_dist = matches.value_of("target_dist");
 
-    let ray_dir =
-        if matches.is_present("output_horizon") || matches.is_present("output_horizon_dist") {
-            RayDir::Horizon
-        } else {
-            match (start_angle, tgt_h, tgt_dist) {
-                (Some(ang), None, None) => RayDir::Angle(
-                    ang.parse()
-                        .ok()
-                        .expect("Invalid angle passed to --start-angle"),
-                ),
-                (None, Some(h), Some(dist)) => RayDir::Target {
-                    h: h.parse().ok().expect("Invalid altitude passed to --tgt-h"),
-                    dist: dist
-                        .parse::<f64>()
-                        .ok()
-                        .expect("Invalid distance passed to --tgt-dist")
-                        * 1e3,
-                },
-                (None, None, None) => panic!("No ray direction chosen!"),
-                _ => panic!("Conflicting options detected (--start-angle, --tgt-h, --tgt-dist)"),
+    let fan = match (
+        matches.value_of("fan_min_angle"),
+        matches.value_of("fan_max_angle"),
+        matches.value_of("fan_count"),
+        matches.value_of("fan_step"),
+        matches.value_of("fan_max_dist"),
+        matches.value_of("fan_output"),
+    ) {
+        (None, None, None, None, None, None) => None,
+        (Some(min_angle), Some(max_angle), Some(count), Some(ds), Some(dmax), Some(output_dir)) => {
+            let ds: f64 = ds
+                .parse()
+                .ok()
+                .expect("Invalid step passed to --fan-step");
+            if ds <= 0.0 {
+                panic!("--fan-step must be positive, got {}", ds);
             }
-        };
+            Some(FanParams {
+                min_angle: min_angle
+                    .parse()
+                    .ok()
+                    .expect("Invalid angle passed to --fan-min-angle"),
+                max_angle: max_angle
+                    .parse()
+                    .ok()
+                    .expect("Invalid angle passed to --fan-max-angle"),
+                count: count
+                    .parse()
+                    .ok()
+                    .expect("Invalid count passed to --fan-count"),
+                ds: ds * 1e3,
+                dmax: dmax
+                    .parse::<f64>()
+                    .ok()
+                    .expect("Invalid distance passed to --fan-max-dist")
+                    * 1e3,
+                output_dir: output_dir.to_string(),
+            })
+        }
+        _ => panic!(
+            "Incomplete fan launch options: --fan-min-angle, --fan-max-angle, --fan-count, \
+             --fan-step, --fan-max-dist and --fan-output must all be given together"
+        ),
+    };
+
+    let wavelength = matches
+        .value_of("wavelength")
+        .map(|val| {
+            val.parse::<f64>()
+                .ok()
+                .expect("Invalid wavelength passed to --wavelength")
+                * 1e-9
+        }).unwrap_or(530e-9);
+
+    let scenario = matches.value_of("scenario").map(get_scenario);
+
+    let format = match matches.value_of("format") {
+        None | Some("text") => Format::Text,
+        Some("json") => Format::Json,
+        Some("csv") => Format::Csv,
+        Some(other) => panic!("Unknown output format {:?}", other),
+    };
+
+    let ray_dir = if fan.is_some() || scenario.is_some() {
+        // unused when a fan launch or a scenario file is requested; main()
+        // dispatches to that subsystem before a single ray is ever cast
+        RayDir::Angle(0.0)
+    } else if matches.is_present("output_horizon") || matches.is_present("output_horizon_dist") {
+        RayDir::Horizon
+    } else {
+        match (start_angle, tgt_h, tgt_dist) {
+            (Some(ang), None, None) => RayDir::Angle(
+                ang.parse()
+                    .ok()
+                    .expect("Invalid angle passed to --start-angle"),
+            ),
+            (None, Some(h), Some(dist)) => RayDir::Target {
+                h: h.parse().ok().expect("Invalid altitude passed to --tgt-h"),
+                dist: dist
+                    .parse::<f64>()
+                    .ok()
+                    .expect("Invalid distance passed to --tgt-dist")
+                    * 1e3,
+            },
+            (None, None, None) => panic!("No ray direction chosen!"),
+            _ => panic!("Conflicting options detected (--start-angle, --tgt-h, --tgt-dist)"),
+        }
+    };
     let ray = RayData {
         start_h,
         dir: ray_dir,
@@ -201,6 +432,56 @@ pub fn parse_arguments() -> Params {
     if matches.is_present("output_astronomical") {
         output.push(Output::Astronomical);
     }
+    if let Some(mut wavelengths) = matches.values_of("output_dispersion") {
+        let wavelength1: f64 = wavelengths
+            .next()
+            .unwrap()
+            .parse()
+            .ok()
+            .expect("Invalid wavelength passed to --output-dispersion");
+        let wavelength2: f64 = wavelengths
+            .next()
+            .unwrap()
+            .parse()
+            .ok()
+            .expect("Invalid wavelength passed to --output-dispersion");
+        output.push(Output::Dispersion {
+            wavelength1: wavelength1 * 1e-9,
+            wavelength2: wavelength2 * 1e-9,
+        });
+    }
+    if let Some(terrain_file) = matches.value_of("visibility_terrain") {
+        let ds = matches
+            .value_of("visibility_ds")
+            .and_then(|val| val.parse::<f64>().ok())
+            .expect("--visibility-ds is required together with --visibility-terrain")
+            * 1e3;
+        if ds <= 0.0 {
+            panic!("--visibility-ds must be positive, got {}", ds / 1e3);
+        }
+        let dmax = matches
+            .value_of("visibility_dmax")
+            .and_then(|val| val.parse::<f64>().ok())
+            .expect("--visibility-dmax is required together with --visibility-terrain")
+            * 1e3;
+        let tgt_dist = matches
+            .value_of("visibility_tgt_dist")
+            .and_then(|val| val.parse::<f64>().ok())
+            .expect("--visibility-tgt-dist is required together with --visibility-terrain")
+            * 1e3;
+        let tgt_h = matches
+            .value_of("visibility_tgt_h")
+            .and_then(|val| val.parse::<f64>().ok())
+            .expect("--visibility-tgt-h is required together with --visibility-terrain");
+
+        output.push(Output::Visibility {
+            terrain: TerrainProfile::load(terrain_file),
+            ds,
+            dmax,
+            tgt_dist,
+            tgt_h,
+        });
+    }
     if matches.is_present("output_horizon") {
         output = vec![Output::HorizonAngle];
     }
@@ -213,26 +494,35 @@ pub fn parse_arguments() -> Params {
         env: Environment {
             shape,
             atmosphere,
-            wavelength: 530e-9,
+            wavelength,
         },
         output,
         verbose: matches.is_present("verbose"),
+        fan,
+        scenario,
+        format,
     }
 }
 
 pub fn create_path<'a>(params: &'a Params) -> Box<dyn Path<'a> + 'a> {
-    match params.ray.dir {
-        RayDir::Angle(ang) => {
-            params
-                .env
-                .cast_ray(params.ray.start_h, ang.to_radians(), params.straight)
-        }
-        RayDir::Target { h, dist } => {
-            params
-                .env
-                .cast_ray_target(params.ray.start_h, h, dist, params.straight)
-        }
-        RayDir::Horizon => params.env.cast_ray(0.0, 0.0, params.straight),
+    cast_ray(
+        &params.env,
+        params.ray.start_h,
+        &params.ray.dir,
+        params.straight,
+    )
+}
+
+pub fn cast_ray<'a>(
+    env: &'a Environment,
+    start_h: f64,
+    dir: &RayDir,
+    straight: bool,
+) -> Box<dyn Path<'a> + 'a> {
+    match *dir {
+        RayDir::Angle(ang) => env.cast_ray(start_h, ang.to_radians(), straight),
+        RayDir::Target { h, dist } => env.cast_ray_target(start_h, h, dist, straight),
+        RayDir::Horizon => env.cast_ray(0.0, 0.0, straight),
     }
 }
 
@@ -246,3 +536,61 @@ fn get_atmosphere(path: &str) -> Atmosphere {
     let def = serde_yaml::from_str::<AtmosphereDef>(&contents).expect("failed parsing config file");
     Atmosphere::from_def(def)
 }
+
+fn get_scenario(path: &str) -> Scenario {
+    let mut scenario_file =
+        File::open(path).unwrap_or_else(|_| panic!("couldn't open the scenario file {:?}", path));
+    let mut contents = String::new();
+    scenario_file
+        .read_to_string(&mut contents)
+        .unwrap_or_else(|_| panic!("failed reading from file {:?}", path));
+    let mut scenario: Scenario =
+        serde_yaml::from_str(&contents).expect("failed parsing scenario file");
+    for case in &mut scenario.cases {
+        normalize_dir_units(&mut case.dir);
+        for output in &mut case.output {
+            normalize_output_units(output);
+        }
+    }
+    scenario
+}
+
+/// `RayDir::Target.dist` uses the same convention as `--tgt-dist`
+/// (kilometers), converted here for the same reason as `normalize_output_units`.
+fn normalize_dir_units(dir: &mut RayDir) {
+    if let RayDir::Target { ref mut dist, .. } = *dir {
+        *dist *= 1e3;
+    }
+}
+
+/// Scenario YAML fields use the same human-friendly units as the matching
+/// CLI flags (kilometers, nanometers) rather than the SI units `Output` is
+/// evaluated in; convert in place here so a scenario case and the
+/// corresponding `--output-*` flag agree on what a number means.
+fn normalize_output_units(output: &mut Output) {
+    match *output {
+        Output::HAtDist(ref mut dist) => *dist *= 1e3,
+        Output::Visibility {
+            ref mut terrain,
+            ref mut ds,
+            ref mut dmax,
+            ref mut tgt_dist,
+            ..
+        } => {
+            for point in &mut terrain.points {
+                point.0 *= 1e3;
+            }
+            *ds *= 1e3;
+            *dmax *= 1e3;
+            *tgt_dist *= 1e3;
+        }
+        Output::Dispersion {
+            ref mut wavelength1,
+            ref mut wavelength2,
+        } => {
+            *wavelength1 *= 1e-9;
+            *wavelength2 *= 1e-9;
+        }
+        Output::Angle | Output::HorizonAngle | Output::HorizonDistance | Output::Astronomical => {}
+    }
+}
@@ -0,0 +1,78 @@
+use crate::params::{cast_ray, Output, RayDir};
+use atm_refraction::{EarthShape, Environment, Path};
+
+/// Binary-search along `ray` for the distance at which it reaches altitude `tgt_h`.
+pub fn find_dist_for_h(ray: &dyn Path, tgt_h: f64) -> f64 {
+    let (mut min_dist, mut max_dist) = (0.0, 5000e3);
+
+    while max_dist - min_dist > 0.00001 {
+        let cur_dist = 0.5 * (min_dist + max_dist);
+        let h = ray.h_at_dist(cur_dist);
+        if h > tgt_h {
+            max_dist = cur_dist;
+        } else {
+            min_dist = cur_dist;
+        }
+    }
+
+    0.5 * (min_dist + max_dist)
+}
+
+/// Angular deflection (radians) of `ray` integrated up to 200 km, the
+/// astronomical-refraction calculation shared by `Output::Astronomical` and
+/// `dispersion` below.
+fn astronomical_deflection(ray: &dyn Path, env: &Environment) -> f64 {
+    let start_ang = ray.angle_at_dist(0.0);
+    let dist_to_200km = find_dist_for_h(ray, 2e5); // 2e5 m == 200 km
+    let final_ang = ray.angle_at_dist(dist_to_200km);
+    if let EarthShape::Spherical { radius } = env.shape {
+        start_ang - final_ang + dist_to_200km / radius
+    } else {
+        start_ang - final_ang
+    }
+}
+
+/// Cast a ray at each of `wavelength1`/`wavelength2` (meters), same starting
+/// altitude, direction and atmosphere otherwise, and return the difference
+/// between their astronomical deflection angles (degrees) - the chromatic
+/// spread that smears a low-altitude star or the solar limb into a spectrum.
+pub fn dispersion(
+    env: &Environment,
+    start_h: f64,
+    dir: &RayDir,
+    straight: bool,
+    wavelength1: f64,
+    wavelength2: f64,
+) -> f64 {
+    let mut env1 = env.clone();
+    env1.wavelength = wavelength1;
+    let mut env2 = env.clone();
+    env2.wavelength = wavelength2;
+
+    let ray1 = cast_ray(&env1, start_h, dir, straight);
+    let ray2 = cast_ray(&env2, start_h, dir, straight);
+
+    (astronomical_deflection(&*ray1, &env1) - astronomical_deflection(&*ray2, &env2)).to_degrees()
+}
+
+/// Evaluate a single `Output` variant against an already-cast `ray`, returning
+/// the raw numeric value (angles in degrees, distances in the same units as
+/// the rest of the CLI - kilometers).
+pub fn eval_output(ray: &dyn Path, env: &Environment, start_h: f64, output: &Output) -> f64 {
+    match *output {
+        Output::HAtDist(dist) => ray.h_at_dist(dist),
+        Output::Angle => ray.angle_at_dist(0.0).to_degrees(),
+        Output::Astronomical => astronomical_deflection(ray, env).to_degrees(),
+        Output::HorizonAngle => {
+            let dist_to_target_h = find_dist_for_h(ray, start_h);
+            -ray.angle_at_dist(dist_to_target_h).to_degrees()
+        }
+        Output::HorizonDistance => find_dist_for_h(ray, start_h) / 1000.0,
+        Output::Visibility { .. } => {
+            unreachable!("Output::Visibility is evaluated through visibility::check_visibility, not eval_output")
+        }
+        Output::Dispersion { .. } => {
+            unreachable!("Output::Dispersion is evaluated through query::dispersion, not eval_output")
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::params::{cast_ray, Format, Output, Params, RayDir, Scenario};
+use crate::query::{dispersion, eval_output};
+use crate::visibility::check_visibility;
+use atm_refraction::{Environment, Path};
+use serde_derive::Serialize;
+
+/// the computed outputs for one scenario `Case`, in request order
+#[derive(Serialize)]
+struct CaseResult {
+    name: String,
+    values: Vec<(String, f64)>,
+}
+
+/// Run every case described by `scenario` against its shared environment and
+/// print the results in `params.format`.
+pub fn run_scenario(params: &Params, scenario: &Scenario) {
+    let results: Vec<CaseResult> = scenario
+        .cases
+        .iter()
+        .map(|case| {
+            let ray = cast_ray(&scenario.env, case.start_h, &case.dir, params.straight);
+            let values = case
+                .output
+                .iter()
+                .flat_map(|output| {
+                    output_values(
+                        &*ray,
+                        &scenario.env,
+                        case.start_h,
+                        &case.dir,
+                        params.straight,
+                        output,
+                    )
+                }).collect();
+            CaseResult {
+                name: case.name.clone(),
+                values,
+            }
+        })
+        .collect();
+
+    match params.format {
+        Format::Text => print_text(&results),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&results)
+                .expect("failed serializing scenario results to JSON")
+        ),
+        Format::Csv => print_csv(&results),
+    }
+}
+
+// Visibility reports two values (an occlusion distance and a visible/blocked
+// flag) rather than the single number every other `Output` variant produces,
+// and Dispersion needs to cast two rays at different wavelengths rather than
+// reusing the one already cast for `case` - both are special-cased here
+// rather than going through `eval_output`.
+fn output_values(
+    ray: &dyn Path,
+    env: &Environment,
+    start_h: f64,
+    dir: &RayDir,
+    straight: bool,
+    output: &Output,
+) -> Vec<(String, f64)> {
+    if let Output::Visibility {
+        ref terrain,
+        ds,
+        dmax,
+        tgt_dist,
+        tgt_h,
+    } = *output
+    {
+        let visibility = check_visibility(ray, terrain, ds, dmax, tgt_dist, tgt_h);
+        return vec![
+            (
+                "visibility_occlusion_dist".to_string(),
+                visibility
+                    .occlusion_dist
+                    .map(|d| d / 1000.0)
+                    .unwrap_or(-1.0),
+            ),
+            (
+                "visibility_target_visible".to_string(),
+                if visibility.target_visible { 1.0 } else { 0.0 },
+            ),
+        ];
+    }
+
+    if let Output::Dispersion {
+        wavelength1,
+        wavelength2,
+    } = *output
+    {
+        let value = dispersion(env, start_h, dir, straight, wavelength1, wavelength2);
+        return vec![("dispersion".to_string(), value)];
+    }
+
+    vec![(output_label(output), eval_output(ray, env, start_h, output))]
+}
+
+fn output_label(output: &Output) -> String {
+    match *output {
+        Output::HAtDist(dist) => format!("h_at_dist({})", dist),
+        Output::Angle => "angle".to_string(),
+        Output::HorizonAngle => "horizon_angle".to_string(),
+        Output::HorizonDistance => "horizon_distance".to_string(),
+        Output::Astronomical => "astronomical".to_string(),
+        Output::Visibility { .. } => unreachable!("handled in output_values"),
+        Output::Dispersion { .. } => unreachable!("handled in output_values"),
+    }
+}
+
+fn print_text(results: &[CaseResult]) {
+    for result in results {
+        println!("{}:", result.name);
+        for (label, value) in &result.values {
+            println!("  {}: {}", label, value);
+        }
+    }
+}
+
+// One row per (case, output) pair, since different cases may request
+// different outputs and therefore don't share a fixed column layout.
+fn print_csv(results: &[CaseResult]) {
+    println!("name,output,value");
+    for result in results {
+        for (label, value) in &result.values {
+            println!("{},{},{}", csv_field(&result.name), csv_field(label), value);
+        }
+    }
+}
+
+/// Quote `field` per RFC 4180 if it contains a comma, quote or newline,
+/// doubling any embedded quotes, so a scenario case name like `"a, b"`
+/// can't corrupt the CSV column layout.
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
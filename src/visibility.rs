@@ -0,0 +1,149 @@
+use atm_refraction::Path;
+use serde_derive::Deserialize;
+use std::{fs::File, io::Read};
+
+/// An ordered list of `(distance, elevation)` terrain samples, both in meters.
+#[derive(Deserialize)]
+pub struct TerrainProfile {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl TerrainProfile {
+    /// Load a terrain profile from a `distance_km,elevation_m` CSV file.
+    pub fn load(path: &str) -> TerrainProfile {
+        let mut file = File::open(path)
+            .unwrap_or_else(|_| panic!("couldn't open the terrain profile file {:?}", path));
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .unwrap_or_else(|_| panic!("failed reading from file {:?}", path));
+
+        let points = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.split(',');
+                let dist_km: f64 = fields
+                    .next()
+                    .expect("missing distance field in terrain profile")
+                    .trim()
+                    .parse()
+                    .expect("invalid distance in terrain profile");
+                let elevation: f64 = fields
+                    .next()
+                    .expect("missing elevation field in terrain profile")
+                    .trim()
+                    .parse()
+                    .expect("invalid elevation in terrain profile");
+                (dist_km * 1e3, elevation)
+            })
+            .collect::<Vec<_>>();
+
+        if points.is_empty() {
+            panic!("terrain profile file {:?} has no data points", path);
+        }
+
+        TerrainProfile { points }
+    }
+
+    /// Linearly interpolate the terrain elevation at `dist` meters.
+    fn elevation_at(&self, dist: f64) -> f64 {
+        if dist <= self.points[0].0 {
+            return self.points[0].1;
+        }
+        for window in self.points.windows(2) {
+            let (d0, e0) = window[0];
+            let (d1, e1) = window[1];
+            if dist <= d1 {
+                let t = (dist - d0) / (d1 - d0);
+                return e0 + t * (e1 - e0);
+            }
+        }
+        self.points.last().unwrap().1
+    }
+}
+
+/// Result of checking a ray's path against a terrain profile.
+pub struct Visibility {
+    /// distance (meters) at which the ray first dips to or below the terrain, if any
+    pub occlusion_dist: Option<f64>,
+    /// whether the target at the checked `(tgt_dist, tgt_h)` is visible
+    pub target_visible: bool,
+}
+
+/// March `ray` over `terrain` in `ds`-sized steps up to the larger of `dmax`
+/// and `tgt_dist`, bisecting
+/// within the bracketing step (like `find_dist_for_h`) to locate the grazing
+/// distance precisely, then report whether a target at `(tgt_dist, tgt_h)` is
+/// visible given that occlusion point.
+pub fn check_visibility(
+    ray: &dyn Path,
+    terrain: &TerrainProfile,
+    ds: f64,
+    dmax: f64,
+    tgt_dist: f64,
+    tgt_h: f64,
+) -> Visibility {
+    // An occlusion beyond `dmax` but before `tgt_dist` would otherwise go
+    // unseen, silently reporting the target as visible; always scan at
+    // least out to the target itself.
+    let search_dmax = dmax.max(tgt_dist);
+    let occlusion_dist = find_occlusion_dist(ray, terrain, ds, search_dmax);
+    let not_occluded_before_target = occlusion_dist.map_or(true, |dist| tgt_dist <= dist);
+    let target_above_terrain = tgt_h > terrain.elevation_at(tgt_dist);
+
+    Visibility {
+        occlusion_dist,
+        target_visible: not_occluded_before_target && target_above_terrain,
+    }
+}
+
+fn find_occlusion_dist(
+    ray: &dyn Path,
+    terrain: &TerrainProfile,
+    ds: f64,
+    dmax: f64,
+) -> Option<f64> {
+    let mut dist = 0.0;
+    let mut prev_diff = ray.h_at_dist(dist) - terrain.elevation_at(dist);
+
+    if prev_diff <= 0.0 {
+        // Already at or below the terrain at the very first sample - there's
+        // no positive->non-positive transition to bisect within, but the ray
+        // is occluded right from the start.
+        return Some(dist);
+    }
+
+    while dist < dmax {
+        let next_dist = (dist + ds).min(dmax);
+        let diff = ray.h_at_dist(next_dist) - terrain.elevation_at(next_dist);
+
+        if prev_diff > 0.0 && diff <= 0.0 {
+            return Some(bisect_occlusion(ray, terrain, dist, next_dist));
+        }
+
+        dist = next_dist;
+        prev_diff = diff;
+    }
+
+    None
+}
+
+fn bisect_occlusion(
+    ray: &dyn Path,
+    terrain: &TerrainProfile,
+    mut min_dist: f64,
+    mut max_dist: f64,
+) -> f64 {
+    while max_dist - min_dist > 0.00001 {
+        let cur_dist = 0.5 * (min_dist + max_dist);
+        let diff = ray.h_at_dist(cur_dist) - terrain.elevation_at(cur_dist);
+        if diff > 0.0 {
+            min_dist = cur_dist;
+        } else {
+            max_dist = cur_dist;
+        }
+    }
+
+    0.5 * (min_dist + max_dist)
+}